@@ -8,10 +8,17 @@ use bbq2::{
     traits::{coordination::cas::AtomicCoord, notifier::maitake::MaiNotSpsc, storage::Inline},
 
 };
+use core::cell::UnsafeCell;
 use core::default::Default;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use cortex_m::peripheral::NVIC;
+use maitake_sync::WaitCell;
 use nrf_pac::Interrupt;
 
+/// Number of pipes ESB addresses, and the size of the per-pipe waker array
+/// backing [`EsbAppReceiver::wait_read_pipe`].
+const NUM_PIPES: usize = 8;
+
 pub(crate) type FramedProducer<const N: usize> = bbq2::prod_cons::framed::FramedProducer<
     &'static BBQueue<Inline<N>, AtomicCoord, MaiNotSpsc>,
     Inline<N>,
@@ -27,6 +34,172 @@ pub(crate) type FramedConsumer<const N: usize> = bbq2::prod_cons::framed::Framed
     u16,
 >;
 
+/// Snapshot of how full one of the `app_to_radio`/`radio_to_app` queues is.
+///
+/// Returned by [`EsbAppSender::tx_limits`]/[`EsbAppReceiver::rx_limits`]
+/// (and the equivalent methods on the unsplit [`EsbApp`]) so applications
+/// can implement backpressure -- e.g. stop generating outgoing packets
+/// once `free_bytes` gets low -- instead of spinning on a failed
+/// `grant_packet`.
+///
+/// `free_bytes` is a hint, not a guarantee: it doesn't account for the
+/// queue's per-frame framing overhead (the length prefix `FramedProducer`
+/// adds on top of each grant), so it can overstate how much room a
+/// following `grant_packet` actually has. Treat it as "getting close to
+/// full", not as a pre-check whose success a caller can rely on -- callers
+/// that need a real reservation should call `grant_packet`/`wait_grant_packet`
+/// and handle its own `Result`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferLimits {
+    /// Bytes currently occupied by committed, not-yet-read frames
+    /// (including their [`PayloadHeader`](crate::payload::PayloadHeader)s).
+    pub len_bytes: usize,
+    /// Bytes still free for new frames to be granted into.
+    ///
+    /// See the caveat on [`BufferLimits`] itself: this is a hint, not a
+    /// guarantee that a grant of this size will succeed.
+    pub free_bytes: usize,
+    /// Total capacity of the underlying queue.
+    pub capacity_bytes: usize,
+    /// Number of complete frames currently queued.
+    pub frame_count: usize,
+}
+
+impl BufferLimits {
+    fn new(capacity_bytes: usize, len_bytes: usize, frame_count: usize) -> Self {
+        Self {
+            len_bytes,
+            free_bytes: capacity_bytes.saturating_sub(len_bytes),
+            capacity_bytes,
+            frame_count,
+        }
+    }
+}
+
+/// Resolves to `Ready` the second time it's polled.
+///
+/// Used to give the executor a chance to run other tasks between retries of
+/// a lock check (the lock is only ever held across an `.await` by whichever
+/// task is parked in `wait_read`; see [`ConsLock`]). The retry loop is not
+/// bounded: while that holder waits on an empty queue, the retrying task is
+/// rescheduled on every poll until the next frame is committed.
+struct YieldOnce(bool);
+
+impl core::future::Future for YieldOnce {
+    type Output = ();
+
+    fn poll(mut self: core::pin::Pin<&mut Self>, cx: &mut core::task::Context<'_>) -> core::task::Poll<()> {
+        if self.0 {
+            core::task::Poll::Ready(())
+        } else {
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            core::task::Poll::Pending
+        }
+    }
+}
+
+async fn yield_once() {
+    YieldOnce(false).await
+}
+
+/// Guards a [`FramedConsumer`] behind a spinlock so [`EsbAppReceiver`] can
+/// be shared (via `&self`) across several tasks, each filtering the same
+/// underlying stream for their own pipe -- see
+/// [`EsbAppReceiver::wait_read_pipe`].
+///
+/// There is still only one physical consumer underneath (the queue has a
+/// single read cursor), so this only serializes access; it does not let
+/// two tasks dequeue different frames at the same instant.
+struct ConsLock<const IN: usize> {
+    busy: AtomicBool,
+    cons: UnsafeCell<FramedConsumer<IN>>,
+    /// Best-effort cache of the occupancy last observed while holding
+    /// `busy`, so [`Self::snapshot`] can report something for `rx_limits`
+    /// without blocking -- and without ever reading `cons` unlocked, which
+    /// would alias the `&mut` a parked `wait_read` holds across its
+    /// `.await` (see the safety note on the `Sync` impl below).
+    last_len_bytes: AtomicUsize,
+    last_frame_count: AtomicUsize,
+}
+
+// SAFETY: `cons` is only ever accessed through `&mut self` (which already
+// implies exclusive access) or after winning the `busy` compare-exchange
+// below, so there is never more than one live reference to it. Unlike
+// `cons`, `last_len_bytes`/`last_frame_count` are plain atomics and may be
+// read or written from any number of threads concurrently.
+unsafe impl<const IN: usize> Sync for ConsLock<IN> {}
+
+impl<const IN: usize> ConsLock<IN> {
+    fn new(cons: FramedConsumer<IN>) -> Self {
+        Self {
+            busy: AtomicBool::new(false),
+            cons: UnsafeCell::new(cons),
+            last_len_bytes: AtomicUsize::new(0),
+            last_frame_count: AtomicUsize::new(0),
+        }
+    }
+
+    fn get_mut(&mut self) -> &mut FramedConsumer<IN> {
+        self.cons.get_mut()
+    }
+
+    fn try_lock(&self) -> Option<ConsGuard<'_, IN>> {
+        self.busy
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|()| ConsGuard(self))
+    }
+
+    /// Reports the consumer's current `(len_bytes, frame_count)` for
+    /// `rx_limits`, without ever reading `cons` while a guard could be
+    /// alive elsewhere.
+    ///
+    /// Takes the lock like any other access when it's free; when it's held
+    /// (most likely by a task parked in `wait_read`), falls back to the
+    /// last values observed under the lock rather than blocking -- `rx_limits`
+    /// is a synchronous backpressure hint, not a precise live counter.
+    fn snapshot(&self) -> (usize, usize) {
+        match self.try_lock() {
+            Some(guard) => {
+                let len_bytes = guard.len();
+                let frame_count = guard.frame_count();
+                self.last_len_bytes.store(len_bytes, Ordering::Relaxed);
+                self.last_frame_count.store(frame_count, Ordering::Relaxed);
+                (len_bytes, frame_count)
+            }
+            None => (
+                self.last_len_bytes.load(Ordering::Relaxed),
+                self.last_frame_count.load(Ordering::Relaxed),
+            ),
+        }
+    }
+}
+
+struct ConsGuard<'a, const IN: usize>(&'a ConsLock<IN>);
+
+impl<const IN: usize> core::ops::Deref for ConsGuard<'_, IN> {
+    type Target = FramedConsumer<IN>;
+
+    fn deref(&self) -> &FramedConsumer<IN> {
+        // SAFETY: see the `Sync` impl on `ConsLock`.
+        unsafe { &*self.0.cons.get() }
+    }
+}
+
+impl<const IN: usize> core::ops::DerefMut for ConsGuard<'_, IN> {
+    fn deref_mut(&mut self) -> &mut FramedConsumer<IN> {
+        // SAFETY: see the `Sync` impl on `ConsLock`.
+        unsafe { &mut *self.0.cons.get() }
+    }
+}
+
+impl<const IN: usize> Drop for ConsGuard<'_, IN> {
+    fn drop(&mut self) {
+        self.0.busy.store(false, Ordering::Release);
+    }
+}
+
 /// This is the primary Application-side interface.
 ///
 /// It is intended to be used outside of the `RADIO` interrupt,
@@ -110,11 +283,19 @@ impl<const OUT: usize> EsbAppSender<OUT> {
     pub fn maximum_payload_size(&self) -> usize {
         self.maximum_payload.into()
     }
+
+    /// Reports how full the outgoing (app -> radio) queue currently is.
+    pub fn tx_limits(&self) -> BufferLimits {
+        BufferLimits::new(OUT, self.prod_to_radio.len(), self.prod_to_radio.frame_count())
+    }
 }
 
 pub struct EsbAppReceiver<const IN: usize> {
-    pub(crate) cons_from_radio: FramedConsumer<IN>,
+    pub(crate) cons_from_radio: ConsLock<IN>,
     pub(crate) maximum_payload: u8,
+    /// One waker per pipe, so [`Self::wait_read_pipe`] can park a task
+    /// instead of re-polling the head of the queue until its pipe shows up.
+    pub(crate) pipe_wakers: [WaitCell; NUM_PIPES],
 }
 
 impl<const IN: usize> EsbAppReceiver<IN> {
@@ -123,7 +304,7 @@ impl<const IN: usize> EsbAppReceiver<IN> {
     /// Returns `true` if a call to `read_packet` would return `Some`.
     pub fn msg_ready(&mut self) -> bool {
         // Dropping the grant does not release it.
-        self.cons_from_radio.read().is_ok()
+        self.cons_from_radio.get_mut().read().is_ok()
     }
 
     /// Attempt to read a packet that has been received via the radio.
@@ -131,11 +312,85 @@ impl<const IN: usize> EsbAppReceiver<IN> {
     /// Returns `Some(PayloadR)` if a packet is ready to be read,
     /// otherwise `None`.
     pub fn read_packet(&mut self) -> Option<PayloadR<IN>> {
-        self.cons_from_radio.read().ok().map(PayloadR::new)
+        self.cons_from_radio.get_mut().read().ok().map(PayloadR::new)
     }
 
     pub async fn wait_read_packet(&mut self) -> PayloadR<IN> {
-        PayloadR::new(self.cons_from_radio.wait_read().await)
+        PayloadR::new(self.cons_from_radio.get_mut().wait_read().await)
+    }
+
+    /// Attempt to read a packet, but only if it was received on `pipe`.
+    ///
+    /// Returns `None` both when the queue is empty, when the next frame in
+    /// line belongs to a different pipe, and when another task currently
+    /// holds the lock (see [`wait_read_pipe`](Self::wait_read_pipe)). In the
+    /// pipe-mismatch case the frame is left at the head of the queue (its
+    /// grant is dropped un-released, exactly like
+    /// [`msg_ready`](Self::msg_ready) peeking) so whoever is waiting on its
+    /// actual pipe can still read it.
+    ///
+    /// ## Head-of-line ordering
+    ///
+    /// `radio_to_app_buf` is a single FIFO shared by all eight pipes: a
+    /// frame for pipe 3 sitting at the head blocks a call for pipe 5 from
+    /// seeing anything behind it, even if pipe 5's data already arrived.
+    /// One pipe that is never drained (no task ever reads it) will
+    /// therefore starve every other pipe once its frames reach the head of
+    /// the queue. Make sure every pipe you configure is read by *something*
+    /// -- even if that's just a `read_pipe` loop that discards the result.
+    pub fn read_pipe(&self, pipe: u8) -> Option<PayloadR<IN>> {
+        let mut guard = self.cons_from_radio.try_lock()?;
+        let payload = PayloadR::new(guard.read().ok()?);
+        if payload.pipe() == pipe {
+            Some(payload)
+        } else {
+            None
+        }
+    }
+
+    /// `await` a packet received on a specific `pipe`, ignoring frames for
+    /// other pipes (see the head-of-line note on [`read_pipe`](Self::read_pipe)).
+    ///
+    /// Takes `&self` (not `&mut self`) so several tasks can each hold their
+    /// own reference to the same [`EsbAppReceiver`] and `await` their own
+    /// pipe independently. There is still only one physical queue
+    /// underneath: the task that holds the [`ConsLock`] reads the head
+    /// frame (parking on the queue's commit notifier if it is empty), and
+    /// if the frame belongs to someone else's pipe it wakes that pipe's
+    /// entry in `pipe_wakers` and then parks on its own. A task that finds
+    /// the lock held by another task does not park; it yields and retries
+    /// until the lock is free. The radio IRQ does not wake `pipe_wakers`:
+    /// a task parked on its pipe's waker is only woken when another task
+    /// peeks the head of the queue and finds a frame for that pipe.
+    pub async fn wait_read_pipe(&self, pipe: u8) -> PayloadR<IN> {
+        let idx = (pipe & 0x07) as usize;
+        loop {
+            match self.cons_from_radio.try_lock() {
+                Some(mut guard) => {
+                    let grant = match guard.read() {
+                        Ok(grant) => grant,
+                        // Queue's empty: park on the queue's own commit
+                        // notifier. Whichever task lands here is effectively
+                        // on duty for noticing the next frame, of any pipe.
+                        Err(_) => guard.wait_read().await,
+                    };
+                    drop(guard);
+                    let payload = PayloadR::new(grant);
+                    if payload.pipe() == pipe {
+                        return payload;
+                    }
+                    // Not ours -- wake its actual owner, then really park
+                    // instead of re-reading this same un-drained frame.
+                    self.pipe_wakers[(payload.pipe() & 0x07) as usize].wake();
+                    drop(payload);
+                    let _ = self.pipe_wakers[idx].wait().await;
+                }
+                // Lock is held elsewhere, most likely by a task parked in
+                // `wait_read` above, which only lets go once anything
+                // commits; until then this yields and retries on every poll.
+                None => yield_once().await,
+            }
+        }
     }
 
     /// Gets the maximum payload size (in bytes) that the driver was configured to use.
@@ -143,6 +398,19 @@ impl<const IN: usize> EsbAppReceiver<IN> {
     pub fn maximum_payload_size(&self) -> usize {
         self.maximum_payload.into()
     }
+
+    /// Reports how full the incoming (radio -> app) queue currently is.
+    ///
+    /// `free_bytes` is a backpressure hint, not a guarantee: it doesn't
+    /// account for per-frame framing overhead, so it can overstate how much
+    /// room the radio side's next grant into this queue actually has. Under
+    /// lock contention (another task parked in `wait_read`) this also falls
+    /// back to the last values observed under the lock rather than blocking
+    /// -- see [`ConsLock::snapshot`].
+    pub fn rx_limits(&self) -> BufferLimits {
+        let (len_bytes, frame_count) = self.cons_from_radio.snapshot();
+        BufferLimits::new(IN, len_bytes, frame_count)
+    }
 }
 
 impl<const OUT: usize, const IN: usize> EsbApp<OUT, IN> {
@@ -150,7 +418,11 @@ impl<const OUT: usize, const IN: usize> EsbApp<OUT, IN> {
         let EsbApp { prod_to_radio, cons_from_radio, maximum_payload } = self;
         (
             EsbAppSender { prod_to_radio, maximum_payload },
-            EsbAppReceiver { cons_from_radio, maximum_payload },
+            EsbAppReceiver {
+                cons_from_radio: ConsLock::new(cons_from_radio),
+                maximum_payload,
+                pipe_wakers: [const { WaitCell::new() }; NUM_PIPES],
+            },
         )
     }
 
@@ -219,11 +491,38 @@ impl<const OUT: usize, const IN: usize> EsbApp<OUT, IN> {
         PayloadR::new(self.cons_from_radio.wait_read().await)
     }
 
+    /// Attempt to read a packet, but only if it was received on `pipe`.
+    ///
+    /// See [`EsbAppReceiver::read_pipe`] for the head-of-line ordering
+    /// caveat that applies here too. There is no `wait_read_pipe` here --
+    /// genuinely waiting for a specific pipe while letting other tasks wait
+    /// on other pipes needs [`split`](Self::split)'s
+    /// [`EsbAppReceiver::wait_read_pipe`], since this unsplit handle can
+    /// only ever be owned by one task at a time.
+    pub fn read_pipe(&mut self, pipe: u8) -> Option<PayloadR<IN>> {
+        let payload = PayloadR::new(self.cons_from_radio.read().ok()?);
+        if payload.pipe() == pipe {
+            Some(payload)
+        } else {
+            None
+        }
+    }
+
     /// Gets the maximum payload size (in bytes) that the driver was configured to use.
     #[inline]
     pub fn maximum_payload_size(&self) -> usize {
         self.maximum_payload.into()
     }
+
+    /// Reports how full the incoming (radio -> app) queue currently is.
+    pub fn rx_limits(&self) -> BufferLimits {
+        BufferLimits::new(IN, self.cons_from_radio.len(), self.cons_from_radio.frame_count())
+    }
+
+    /// Reports how full the outgoing (app -> radio) queue currently is.
+    pub fn tx_limits(&self) -> BufferLimits {
+        BufferLimits::new(OUT, self.prod_to_radio.len(), self.prod_to_radio.frame_count())
+    }
 }
 
 /// Addresses used for communication.
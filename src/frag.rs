@@ -0,0 +1,393 @@
+//! Fragmentation and reassembly for messages larger than one ESB payload.
+//!
+//! A single on-air ESB packet tops out around 252 bytes, so anything bigger
+//! than that can't be sent as one [`grant_packet`](crate::app::EsbAppSender::grant_packet).
+//! [`EsbFragTx`]/[`EsbFragRx`] sit on top of [`EsbAppSender`]/[`EsbAppReceiver`]
+//! and split/reassemble a logical message across several ESB packets, the
+//! same way a chunked firmware-image loader pushes a large image over a
+//! narrow serial link and checks its integrity once fully received.
+//!
+//! Each ESB body carries a small fixed prefix:
+//!
+//! ```text
+//! | msg_id: u16 | frag_index: u16 | frag_count: u16 | ...chunk bytes... | crc32? |
+//! ```
+//!
+//! The trailing `crc32` (little-endian, over the *fully reassembled*
+//! message) is only present on the last fragment (`frag_index == frag_count
+//! - 1`); the receiver can't validate a message until it has that fragment
+//! regardless of arrival order.
+
+use crate::{
+    app::{EsbAppReceiver, EsbAppSender},
+    payload::EsbHeader,
+    Error,
+};
+
+const FRAG_HEADER_LEN: usize = 6;
+const CRC_LEN: usize = 4;
+
+/// Widest `frag_count` a message can be split into: fragments received are
+/// tracked with a `u32` bitmap, one bit per index.
+const MAX_FRAGMENTS: u16 = 32;
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn frag_count_for(len: usize, chunk_cap: usize) -> u16 {
+    let chunk_cap = chunk_cap.max(1);
+    (((len + chunk_cap - 1) / chunk_cap).max(1)) as u16
+}
+
+/// Sends large messages over an [`EsbAppSender`] by splitting them into
+/// fragments.
+pub struct EsbFragTx<const OUT: usize> {
+    sender: EsbAppSender<OUT>,
+    next_msg_id: u16,
+}
+
+impl<const OUT: usize> EsbFragTx<OUT> {
+    pub fn new(sender: EsbAppSender<OUT>) -> Self {
+        Self {
+            sender,
+            next_msg_id: 0,
+        }
+    }
+
+    fn chunk_cap(&self) -> usize {
+        self.sender
+            .maximum_payload_size()
+            .saturating_sub(FRAG_HEADER_LEN + CRC_LEN)
+    }
+
+    /// Splits `data` into fragments tagged with a fresh `msg_id`, queues all
+    /// of them, and kicks off transmission. Returns the `msg_id` so the
+    /// caller can later correlate an [`EsbFragRx::missing_fragments`] report
+    /// with this send when calling [`retransmit`](Self::retransmit).
+    pub fn send<F>(&mut self, mut make_header: F, data: &[u8]) -> Result<u16, Error>
+    where
+        F: FnMut(usize) -> EsbHeader,
+    {
+        let chunk_cap = self.chunk_cap();
+        if chunk_cap == 0 {
+            return Err(Error::MaximumPacketExceeded);
+        }
+        let frag_count = frag_count_for(data.len(), chunk_cap);
+        if frag_count > MAX_FRAGMENTS {
+            return Err(Error::MaximumPacketExceeded);
+        }
+
+        let msg_id = self.next_msg_id;
+        self.next_msg_id = self.next_msg_id.wrapping_add(1);
+        let crc = crc32(data);
+
+        for frag_index in 0..frag_count {
+            self.send_fragment(&mut make_header, data, msg_id, frag_index, frag_count, crc)?;
+        }
+        self.sender.start_tx();
+        Ok(msg_id)
+    }
+
+    /// Re-sends only the fragments listed in `missing` of a message
+    /// previously sent by [`send`](Self::send). The caller must still have
+    /// the exact `data` and `msg_id` that call used -- selective
+    /// retransmission only re-derives *which* bytes to re-send, not the
+    /// bytes themselves.
+    pub fn retransmit<F>(
+        &mut self,
+        mut make_header: F,
+        data: &[u8],
+        msg_id: u16,
+        missing: impl Iterator<Item = u16>,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(usize) -> EsbHeader,
+    {
+        let chunk_cap = self.chunk_cap();
+        if chunk_cap == 0 {
+            return Err(Error::MaximumPacketExceeded);
+        }
+        let frag_count = frag_count_for(data.len(), chunk_cap);
+        let crc = crc32(data);
+
+        for frag_index in missing {
+            self.send_fragment(&mut make_header, data, msg_id, frag_index, frag_count, crc)?;
+        }
+        self.sender.start_tx();
+        Ok(())
+    }
+
+    fn send_fragment<F>(
+        &mut self,
+        make_header: &mut F,
+        data: &[u8],
+        msg_id: u16,
+        frag_index: u16,
+        frag_count: u16,
+        crc: u32,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(usize) -> EsbHeader,
+    {
+        let chunk_cap = self.chunk_cap();
+        let start = (frag_index as usize * chunk_cap).min(data.len());
+        let end = (start + chunk_cap).min(data.len());
+        let chunk = &data[start..end];
+        let is_last = frag_index + 1 == frag_count;
+        let body_len = FRAG_HEADER_LEN + chunk.len() + if is_last { CRC_LEN } else { 0 };
+
+        let mut payload = self.sender.grant_packet(make_header(body_len))?;
+        payload[0..2].copy_from_slice(&msg_id.to_le_bytes());
+        payload[2..4].copy_from_slice(&frag_index.to_le_bytes());
+        payload[4..6].copy_from_slice(&frag_count.to_le_bytes());
+        payload[FRAG_HEADER_LEN..FRAG_HEADER_LEN + chunk.len()].copy_from_slice(chunk);
+        if is_last {
+            let crc_start = FRAG_HEADER_LEN + chunk.len();
+            payload[crc_start..crc_start + CRC_LEN].copy_from_slice(&crc.to_le_bytes());
+        }
+        payload.commit(body_len);
+        Ok(())
+    }
+}
+
+struct ReassemblySlot<const MAX_MSG: usize> {
+    pipe: u8,
+    msg_id: u16,
+    frag_count: u16,
+    /// Bit `i` is set once fragment `i` has been written into `buf`.
+    received: u32,
+    /// Only meaningful once `received` covers `frag_count` bits.
+    total_len: usize,
+    crc: u32,
+    buf: [u8; MAX_MSG],
+    /// Bumped every time this slot is touched; the oldest (smallest) value
+    /// across all slots is evicted first when the pool is full.
+    touched_at: u32,
+}
+
+impl<const MAX_MSG: usize> ReassemblySlot<MAX_MSG> {
+    fn new(pipe: u8, msg_id: u16, frag_count: u16, touched_at: u32) -> Self {
+        Self {
+            pipe,
+            msg_id,
+            frag_count,
+            received: 0,
+            total_len: 0,
+            crc: 0,
+            buf: [0u8; MAX_MSG],
+            touched_at,
+        }
+    }
+}
+
+/// A fully reassembled, CRC-checked message.
+pub struct ReassembledMessage<const MAX_MSG: usize> {
+    pub pipe: u8,
+    pub msg_id: u16,
+    len: usize,
+    buf: [u8; MAX_MSG],
+}
+
+impl<const MAX_MSG: usize> ReassembledMessage<MAX_MSG> {
+    pub fn data(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+/// Fragment indices still missing from an in-progress reassembly, as
+/// reported by [`EsbFragRx::missing_fragments`].
+pub struct MissingFragments {
+    present: u32,
+    frag_count: u16,
+}
+
+impl MissingFragments {
+    /// Iterates the missing `frag_index` values, in order, so the sender
+    /// can selectively retransmit just those fragments.
+    pub fn iter(&self) -> impl Iterator<Item = u16> + '_ {
+        let present = self.present;
+        (0..self.frag_count).filter(move |i| present & (1 << i) == 0)
+    }
+}
+
+/// Reassembles large messages received over an [`EsbAppReceiver`].
+///
+/// `SLOTS` bounds how many distinct pipes can have an in-flight
+/// reassembly tracked at once; `MAX_MSG` bounds the size of a single
+/// reassembled message. Only one in-flight message is tracked *per pipe*:
+/// a fragment with a new `msg_id` on a pipe that already has a
+/// partially-reassembled message discards that partial and starts over,
+/// on the assumption the sender abandoned it. When all `SLOTS` are in use
+/// by other pipes, a fragment for a not-yet-seen pipe evicts whichever
+/// slot was least recently touched.
+pub struct EsbFragRx<const IN: usize, const SLOTS: usize, const MAX_MSG: usize> {
+    receiver: EsbAppReceiver<IN>,
+    slots: [Option<ReassemblySlot<MAX_MSG>>; SLOTS],
+    generation: u32,
+}
+
+impl<const IN: usize, const SLOTS: usize, const MAX_MSG: usize> EsbFragRx<IN, SLOTS, MAX_MSG> {
+    pub fn new(receiver: EsbAppReceiver<IN>) -> Self {
+        Self {
+            receiver,
+            slots: core::array::from_fn(|_| None),
+            generation: 0,
+        }
+    }
+
+    fn chunk_cap(&mut self) -> usize {
+        self.receiver
+            .maximum_payload_size()
+            .saturating_sub(FRAG_HEADER_LEN + CRC_LEN)
+    }
+
+    /// Reports which `frag_index` values are still missing from the
+    /// in-progress reassembly on `pipe`, or `None` if nothing is in
+    /// progress for that pipe.
+    pub fn missing_fragments(&self, pipe: u8) -> Option<MissingFragments> {
+        let slot = self.slots.iter().flatten().find(|s| s.pipe == pipe)?;
+        Some(MissingFragments {
+            present: slot.received,
+            frag_count: slot.frag_count,
+        })
+    }
+
+    fn slot_index_for(&mut self, pipe: u8, msg_id: u16, frag_count: u16) -> usize {
+        self.generation = self.generation.wrapping_add(1);
+        let gen = self.generation;
+
+        if let Some(i) = self.slots.iter().position(|s| matches!(s, Some(s) if s.pipe == pipe)) {
+            let slot = self.slots[i].as_mut().expect("just matched Some above");
+            if slot.msg_id != msg_id {
+                *slot = ReassemblySlot::new(pipe, msg_id, frag_count, gen);
+            } else {
+                slot.touched_at = gen;
+            }
+            return i;
+        }
+
+        if let Some(i) = self.slots.iter().position(Option::is_none) {
+            self.slots[i] = Some(ReassemblySlot::new(pipe, msg_id, frag_count, gen));
+            return i;
+        }
+
+        let i = self
+            .slots
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, s)| s.as_ref().expect("pool is full").touched_at)
+            .map(|(i, _)| i)
+            .expect("SLOTS > 0");
+        self.slots[i] = Some(ReassemblySlot::new(pipe, msg_id, frag_count, gen));
+        i
+    }
+
+    /// Processes exactly one received ESB packet (if any is waiting),
+    /// folding it into its message's reassembly slot. Returns the
+    /// completed, CRC-verified message once its final fragment arrives;
+    /// call this in a loop (e.g. while [`msg_ready`](EsbAppReceiver::msg_ready)
+    /// is true) to drain everything currently queued.
+    pub fn read_message(&mut self) -> Option<ReassembledMessage<MAX_MSG>> {
+        let payload = self.receiver.read_packet()?;
+        let pipe = payload.pipe();
+        let result = self.ingest(pipe, &payload);
+        payload.release();
+        result
+    }
+
+    /// `await`s and processes ESB packets until a complete, CRC-verified
+    /// message is produced.
+    pub async fn wait_read_message(&mut self) -> ReassembledMessage<MAX_MSG> {
+        loop {
+            let payload = self.receiver.wait_read_packet().await;
+            let pipe = payload.pipe();
+            let result = self.ingest(pipe, &payload);
+            payload.release();
+            if let Some(msg) = result {
+                return msg;
+            }
+        }
+    }
+
+    fn ingest(&mut self, pipe: u8, body: &[u8]) -> Option<ReassembledMessage<MAX_MSG>> {
+        if body.len() < FRAG_HEADER_LEN {
+            return None;
+        }
+        let msg_id = u16::from_le_bytes([body[0], body[1]]);
+        let frag_index = u16::from_le_bytes([body[2], body[3]]);
+        let frag_count = u16::from_le_bytes([body[4], body[5]]);
+        if frag_count == 0 || frag_count > MAX_FRAGMENTS || frag_index >= frag_count {
+            return None;
+        }
+
+        let is_last = frag_index + 1 == frag_count;
+        let rest = &body[FRAG_HEADER_LEN..];
+        let (chunk, crc_trailer) = if is_last {
+            if rest.len() < CRC_LEN {
+                return None;
+            }
+            rest.split_at(rest.len() - CRC_LEN)
+        } else {
+            (rest, &[][..])
+        };
+
+        let chunk_cap = self.chunk_cap();
+        if chunk_cap == 0 || chunk.len() > chunk_cap {
+            return None;
+        }
+
+        let idx = self.slot_index_for(pipe, msg_id, frag_count);
+
+        let offset = frag_index as usize * chunk_cap;
+        if offset + chunk.len() > MAX_MSG {
+            // Sender/receiver disagree on maximum_payload_size (or MAX_MSG
+            // is too small for this message); drop it instead of writing
+            // out of bounds.
+            self.slots[idx] = None;
+            return None;
+        }
+        let slot = self.slots[idx].as_mut().expect("just inserted/found above");
+        slot.buf[offset..offset + chunk.len()].copy_from_slice(chunk);
+        // Idempotent: re-writing an already-received fragment overwrites
+        // identical bytes with identical bytes, and setting an
+        // already-set bit is a no-op.
+        slot.received |= 1 << frag_index;
+        if is_last {
+            slot.total_len = offset + chunk.len();
+            let mut crc_bytes = [0u8; CRC_LEN];
+            crc_bytes.copy_from_slice(crc_trailer);
+            slot.crc = u32::from_le_bytes(crc_bytes);
+        }
+
+        let complete_mask = if frag_count == MAX_FRAGMENTS {
+            u32::MAX
+        } else {
+            (1u32 << frag_count) - 1
+        };
+        if slot.received & complete_mask != complete_mask {
+            return None;
+        }
+
+        if crc32(&slot.buf[..slot.total_len]) != slot.crc {
+            self.slots[idx] = None;
+            return None;
+        }
+
+        let ReassemblySlot { buf, total_len, .. } = self.slots[idx].take().expect("checked Some above");
+        Some(ReassembledMessage {
+            pipe,
+            msg_id,
+            len: total_len,
+            buf,
+        })
+    }
+}
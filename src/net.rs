@@ -0,0 +1,211 @@
+//! [`embassy_net_driver::Driver`] implementation over [`EsbApp`].
+//!
+//! This lets an `embassy-net` IP stack run directly on top of an ESB link,
+//! the same way it plugs into the WIZnet/ENC28J60/esp-hosted drivers: every
+//! outgoing IP frame becomes one ESB packet body, and every received ESB
+//! packet (minus its [`PayloadHeader`]) is handed to the stack as one frame.
+//!
+//! ESB has no notion of IP/Ethernet addressing of its own (that's what
+//! [`Addresses`](crate::app::Addresses)/pipes are for), so the caller picks
+//! whatever [`HardwareAddress`] makes sense for their stack and supplies it
+//! at construction time.
+#![cfg(feature = "embassy-net-driver")]
+
+use core::future::Future;
+use core::pin::pin;
+use core::task::Context;
+
+use embassy_net_driver::{Capabilities, Driver, HardwareAddress, LinkState, Medium, RxToken, TxToken};
+
+use crate::{
+    app::{EsbApp, EsbAppReceiver, EsbAppSender},
+    payload::{EsbHeader, PayloadR, PayloadW},
+};
+
+/// The largest body ESB can carry in a single on-air packet.
+///
+/// Used only as a bound for the scratch copy in [`EsbRxToken::consume`];
+/// the real per-link limit is `maximum_payload_size()`.
+const MAX_ESB_PAYLOAD: usize = 252;
+
+/// `embassy-net` [`Driver`] wrapping a split [`EsbApp`].
+///
+/// `F` builds the [`EsbHeader`] (destination pipe, ack policy, ...) for an
+/// outgoing frame. It is called once per token handed out -- from
+/// `transmit()` or the `TxToken` piggybacked on `receive()` -- with the link
+/// MTU as a worst-case length, *not* from `consume()`: the frame's real
+/// length isn't known until `consume` runs, and `consume` must be
+/// infallible, so the grant (and the one `make_header` call it needs) has
+/// to happen up front. Callers that always send on the same pipe can simply
+/// close over a constant pipe number.
+pub struct EsbDriver<const OUT: usize, const IN: usize, F> {
+    sender: EsbAppSender<OUT>,
+    receiver: EsbAppReceiver<IN>,
+    make_header: F,
+    hardware_address: HardwareAddress,
+    mtu: usize,
+}
+
+impl<const OUT: usize, const IN: usize, F> EsbDriver<OUT, IN, F>
+where
+    F: FnMut(usize) -> EsbHeader,
+{
+    /// Wraps an [`EsbApp`], splitting it into its sender/receiver halves.
+    ///
+    /// `mtu()` is `maximum_payload_size()`: that's already the usable body
+    /// size (the cap `grant_packet` checks `EsbHeader::length` against), and
+    /// `grant_packet` adds the on-air [`PayloadHeader`] on top of it, so
+    /// there's no header to subtract here.
+    pub fn new(app: EsbApp<OUT, IN>, hardware_address: HardwareAddress, make_header: F) -> Self {
+        let mtu = app.maximum_payload_size();
+        let (sender, receiver) = app.split();
+        Self {
+            sender,
+            receiver,
+            make_header,
+            hardware_address,
+            mtu,
+        }
+    }
+
+    /// Reserves a fresh outgoing grant sized to the link MTU.
+    ///
+    /// Both `transmit` and `receive` (which must hand back a `TxToken` of
+    /// its own) go through this, so the token they return only ever has to
+    /// trim and commit an already-granted buffer in `consume` -- it never
+    /// attempts a fresh `grant_packet` there that could fail on a valid
+    /// `len` (`PayloadW::commit` shrinks the grant down to the real length
+    /// once `consume` knows it).
+    fn reserve_tx(&mut self) -> Option<PayloadW<OUT>> {
+        let header = (self.make_header)(self.mtu);
+        self.sender.grant_packet(header).ok()
+    }
+
+    fn medium(&self) -> Medium {
+        match self.hardware_address {
+            HardwareAddress::Ethernet(_) => Medium::Ethernet,
+            HardwareAddress::Ieee802154(_) => Medium::Ieee802154,
+            HardwareAddress::Ip => Medium::Ip,
+        }
+    }
+}
+
+impl<const OUT: usize, const IN: usize, F> Driver for EsbDriver<OUT, IN, F>
+where
+    F: FnMut(usize) -> EsbHeader,
+{
+    type RxToken<'a>
+        = EsbRxToken<IN>
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = EsbTxToken<'a, OUT>
+    where
+        Self: 'a;
+
+    fn receive(&mut self, cx: &mut Context<'_>) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        if !self.receiver.msg_ready() {
+            // Piggyback on `wait_read_packet`'s own wake-up: poll it once,
+            // without awaiting, so it registers `cx`'s waker on the queue's
+            // commit notifier before we give up for this poll.
+            let mut fut = pin!(self.receiver.wait_read_packet());
+            if fut.as_mut().poll(cx).is_pending() {
+                return None;
+            }
+            // A frame actually showed up while we were registering -- fall
+            // through and fetch it for real below.
+        }
+        let packet = self.receiver.read_packet()?;
+        // `receive` has to hand back a `TxToken` too (e.g. for ARP/ICMP
+        // replies smoltcp may issue while processing the incoming frame),
+        // and that token's `consume` needs to be just as infallible as
+        // `transmit`'s. If there's no room for a grant right now, leave
+        // `packet` un-released -- same as a pipe mismatch in `read_pipe` --
+        // so it's still there to redeliver on the next poll, and defer this
+        // receive entirely rather than handing out a token that could panic.
+        let payload = self.reserve_tx()?;
+        Some((
+            EsbRxToken(packet),
+            EsbTxToken {
+                sender: &mut self.sender,
+                payload,
+            },
+        ))
+    }
+
+    fn transmit(&mut self, _cx: &mut Context<'_>) -> Option<Self::TxToken<'_>> {
+        let payload = self.reserve_tx()?;
+        Some(EsbTxToken {
+            sender: &mut self.sender,
+            payload,
+        })
+    }
+
+    fn link_state(&mut self, _cx: &mut Context<'_>) -> LinkState {
+        // ESB has no carrier-sense of its own, so approximate it from queue
+        // state: if the outgoing queue is completely full, we can't
+        // currently get frames onto the air. `free_bytes` is only a
+        // backpressure hint (see `BufferLimits`), which is fine here --
+        // this is itself just a coarse heuristic, not a guarantee.
+        if self.sender.tx_limits().free_bytes == 0 {
+            LinkState::Down
+        } else {
+            LinkState::Up
+        }
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        let mut caps = Capabilities::default();
+        caps.medium = self.medium();
+        caps.max_transmission_unit = self.mtu;
+        caps
+    }
+
+    fn hardware_address(&self) -> HardwareAddress {
+        self.hardware_address
+    }
+}
+
+/// [`RxToken`] handing out the body of one received [`PayloadR`].
+pub struct EsbRxToken<const IN: usize>(PayloadR<IN>);
+
+impl<const IN: usize> RxToken for EsbRxToken<IN> {
+    fn consume<R, G>(self, f: G) -> R
+    where
+        G: FnOnce(&mut [u8]) -> R,
+    {
+        let EsbRxToken(payload) = self;
+        // The grant is a read-only view onto committed queue memory, so we
+        // hand the stack a scratch copy rather than trying to mutate it in
+        // place; the grant is released once the copy has been delivered.
+        let mut scratch = [0u8; MAX_ESB_PAYLOAD];
+        let len = payload.len().min(MAX_ESB_PAYLOAD);
+        scratch[..len].copy_from_slice(&payload[..len]);
+        let result = f(&mut scratch[..len]);
+        payload.release();
+        result
+    }
+}
+
+/// [`TxToken`] wrapping a [`PayloadW`] already granted (by
+/// [`EsbDriver::reserve_tx`]) up to the link MTU, so `consume` only has to
+/// trim it down to the real length and commit -- it can never fail on a
+/// valid `len`, satisfying `embassy-net`'s infallibility contract for
+/// `TxToken::consume`.
+pub struct EsbTxToken<'d, const OUT: usize> {
+    sender: &'d mut EsbAppSender<OUT>,
+    payload: PayloadW<OUT>,
+}
+
+impl<'d, const OUT: usize> TxToken for EsbTxToken<'d, OUT> {
+    fn consume<R, G>(self, len: usize, f: G) -> R
+    where
+        G: FnOnce(&mut [u8]) -> R,
+    {
+        let EsbTxToken { sender, mut payload } = self;
+        let result = f(&mut payload[..len]);
+        payload.commit(len);
+        sender.start_tx();
+        result
+    }
+}
@@ -60,7 +60,7 @@ impl PayloadHeader {
         3
     }
 
-    const fn header_size() -> usize {
+    pub(crate) const fn header_size() -> usize {
         core::mem::size_of::<PhBytes>()
     }
 